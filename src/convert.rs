@@ -0,0 +1,108 @@
+//! In-crate software pixel-format conversions.
+//!
+//! The make87 message set does not cover every pairing of decoded layout and
+//! consumer need, so we keep a few cheap conversions here. This lets a single
+//! decode be re-expressed per consumer (e.g. a color stream plus a grayscale
+//! copy for the motion detector or an ML pipeline) without respawning ffmpeg.
+
+/// Converts a planar I420/YUV420 buffer to packed RGB888 (BT.601, limited range).
+pub fn yuv420_to_rgb888(yuv: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let y_plane = &yuv[..w * h];
+    let u_plane = &yuv[w * h..w * h + w * h / 4];
+    let v_plane = &yuv[w * h + w * h / 4..];
+    let chroma = |x: usize, y: usize| {
+        let idx = (y / 2) * (w / 2) + x / 2;
+        (u_plane[idx] as i32 - 128, v_plane[idx] as i32 - 128)
+    };
+    convert_yuv(y_plane, w, h, chroma)
+}
+
+/// Converts a semi-planar NV12 buffer (Y plane + interleaved UV) to RGB888.
+// Intentional public conversion surface: offered so a consumer can re-express a
+// decoded frame without a call site in this binary. `allow(dead_code)` because a
+// `pub` fn in a bin crate is otherwise flagged when unused internally.
+#[allow(dead_code)]
+pub fn nv12_to_rgb888(nv12: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let y_plane = &nv12[..w * h];
+    let uv_plane = &nv12[w * h..];
+    let chroma = |x: usize, y: usize| {
+        let idx = (y / 2) * (w / 2) + x / 2;
+        (uv_plane[idx * 2] as i32 - 128, uv_plane[idx * 2 + 1] as i32 - 128)
+    };
+    convert_yuv(y_plane, w, h, chroma)
+}
+
+/// Extracts the luma channel of packed RGB888 as single-channel GRAY8.
+// Intentional public conversion surface: offered so a consumer can re-express a
+// decoded frame without a call site in this binary. `allow(dead_code)` because a
+// `pub` fn in a bin crate is otherwise flagged when unused internally.
+#[allow(dead_code)]
+pub fn rgb888_to_gray8(rgb: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let mut gray = vec![0u8; w * h];
+    for (i, px) in rgb.chunks_exact(3).take(w * h).enumerate() {
+        let (r, g, b) = (px[0] as u32, px[1] as u32, px[2] as u32);
+        gray[i] = ((77 * r + 150 * g + 29 * b) >> 8) as u8;
+    }
+    gray
+}
+
+/// Shared luma+chroma → RGB888 inner loop, parameterised by how (U, V) are read.
+fn convert_yuv(y_plane: &[u8], w: usize, h: usize, chroma: impl Fn(usize, usize) -> (i32, i32)) -> Vec<u8> {
+    let mut rgb = vec![0u8; w * h * 3];
+    for y in 0..h {
+        for x in 0..w {
+            let c = y_plane[y * w + x] as i32 - 16;
+            let (d, e) = chroma(x, y);
+            let r = (298 * c + 409 * e + 128) >> 8;
+            let g = (298 * c - 100 * d - 208 * e + 128) >> 8;
+            let b = (298 * c + 516 * d + 128) >> 8;
+            let o = (y * w + x) * 3;
+            rgb[o] = r.clamp(0, 255) as u8;
+            rgb[o + 1] = g.clamp(0, 255) as u8;
+            rgb[o + 2] = b.clamp(0, 255) as u8;
+        }
+    }
+    rgb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 2x2 solid-white I420 frame: Y all 235, U/V all 128 (BT.601 white).
+    fn white_i420_2x2() -> Vec<u8> {
+        let mut v = vec![235u8; 4]; // Y plane
+        v.extend_from_slice(&[128, 128]); // one U, one V sample
+        v
+    }
+
+    #[test]
+    fn yuv420_white_is_near_white_rgb() {
+        let rgb = yuv420_to_rgb888(&white_i420_2x2(), 2, 2);
+        assert_eq!(rgb.len(), 2 * 2 * 3);
+        // Every pixel should be (near) white.
+        for px in rgb.chunks_exact(3) {
+            assert!(px.iter().all(|&c| c >= 250));
+        }
+    }
+
+    #[test]
+    fn nv12_matches_yuv420_for_equivalent_input() {
+        // NV12: same Y, with U/V interleaved instead of planar.
+        let mut nv12 = vec![235u8; 4];
+        nv12.extend_from_slice(&[128, 128]);
+        let rgb = nv12_to_rgb888(&nv12, 2, 2);
+        assert_eq!(rgb, yuv420_to_rgb888(&white_i420_2x2(), 2, 2));
+    }
+
+    #[test]
+    fn rgb888_to_gray8_uses_rec601_luma() {
+        // Pure green should map to the Rec.601 green weight (150/256 * 255).
+        let rgb = [0u8, 255, 0];
+        let gray = rgb888_to_gray8(&rgb, 1, 1);
+        assert_eq!(gray, vec![((150 * 255u32) >> 8) as u8]);
+    }
+}
@@ -1,3 +1,7 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use anyhow::Result;
 use ffmpeg_sidecar::command::FfmpegCommand;
 use ffmpeg_sidecar::event::FfmpegEvent;
@@ -5,12 +9,24 @@ use futures::stream::{SelectAll, StreamExt};
 use log::{debug, error, info, trace, warn};
 use make87_messages::core::Header;
 use make87_messages::google::protobuf::Timestamp;
-use make87_messages::image::uncompressed::{ImageRawAny, ImageRgb888, ImageYuv420};
+use make87_messages::image::compressed::ImageJPEG;
+use make87_messages::image::uncompressed::{ImageGray8, ImageNv12, ImageRawAny, ImageRgb888, ImageYuv420};
 use tokio::sync::watch;
 use tokio::task;
 use tokio_stream::wrappers::WatchStream;
 use url::Url;
 
+mod convert;
+mod probe;
+mod recording;
+mod retina_backend;
+mod transcode;
+
+use probe::StreamInfo;
+use recording::{Recorder, RecordingConfig, RecordingFinished};
+use transcode::CompressedFrame;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
 type FrameSender = watch::Sender<Option<ImageRawAny>>;
 struct CameraConfig {
     ip: Vec<String>,
@@ -19,11 +35,81 @@ struct CameraConfig {
     username: Vec<String>,
     password: Vec<String>,
     stream_index: Vec<u32>,
+    backend: RtspBackend,
+    transport: RtspTransport,
+    reconnect_initial_backoff_ms: u64,
+    reconnect_max_backoff_ms: u64,
+    recording: RecordingConfig,
+    output_mode: OutputMode,
+    transcode: Vec<TranscodeConfig>,
+}
+
+/// Which RTSP demuxer to use for pulling frames off the wire.
+enum RtspBackend {
+    /// Shell out to an external ffmpeg process (the historical default).
+    Ffmpeg,
+    /// In-process pure-Rust demuxer backed by the `retina` crate.
+    Retina,
+}
+
+impl Clone for RtspBackend {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for RtspBackend {}
+
+impl RtspBackend {
+    fn from_str(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "retina" => RtspBackend::Retina,
+            _ => RtspBackend::Ffmpeg,
+        }
+    }
+}
+
+/// Lower-level transport RTP is carried over.
+enum RtspTransport {
+    /// Interleaved over the RTSP TCP connection (robust through NAT).
+    Tcp,
+    /// Separate UDP flows (lower latency, but lossy on bad networks).
+    Udp,
+}
+
+impl Clone for RtspTransport {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for RtspTransport {}
+
+impl RtspTransport {
+    fn from_str(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => RtspTransport::Udp,
+            _ => RtspTransport::Tcp,
+        }
+    }
+
+    /// The value ffmpeg expects for its `-rtsp_transport` flag.
+    fn ffmpeg_flag(&self) -> &'static str {
+        match self {
+            RtspTransport::Tcp => "tcp",
+            RtspTransport::Udp => "udp",
+        }
+    }
 }
 
 enum ImageFormat {
     Rgb888,
     Yuv420,
+    Nv12,
+    /// Single-channel luma — handy for ML pipelines and the motion detector.
+    Gray8,
+    /// Motion-JPEG passthrough: each frame is an independent JPEG.
+    Mjpeg,
 }
 
 impl Clone for ImageFormat {
@@ -38,9 +124,128 @@ impl ImageFormat {
     fn from_str(s: &str) -> Self {
         match s.to_ascii_uppercase().as_str() {
             "YUV420" => ImageFormat::Yuv420,
+            "NV12" => ImageFormat::Nv12,
+            "GRAY8" => ImageFormat::Gray8,
+            "MJPEG" => ImageFormat::Mjpeg,
             _ => ImageFormat::Rgb888,
         }
     }
+
+    /// The ffmpeg `-pix_fmt` name for this format.
+    fn pix_fmt(&self) -> &'static str {
+        match self {
+            ImageFormat::Rgb888 => "rgb24",
+            ImageFormat::Yuv420 => "yuv420p",
+            ImageFormat::Nv12 => "nv12",
+            ImageFormat::Gray8 => "gray",
+            // MJPEG output is JPEG-encoded by ffmpeg from this full-range
+            // 4:2:0 layout.
+            ImageFormat::Mjpeg => "yuvj420p",
+        }
+    }
+
+    /// Whether this format carries compressed (JPEG) rather than raw pixels.
+    fn is_mjpeg(&self) -> bool {
+        matches!(self, ImageFormat::Mjpeg)
+    }
+
+    /// Wraps a decoded plane (or JPEG frame) into the tagged `ImageRawAny` we
+    /// publish.
+    fn wrap(&self, header: Option<Header>, width: u32, height: u32, data: Vec<u8>) -> ImageRawAny {
+        use make87_messages::image::uncompressed::image_raw_any::Image;
+        let image = match self {
+            ImageFormat::Rgb888 => Image::Rgb888(ImageRgb888 {
+                header: header.clone(),
+                width,
+                height,
+                data,
+            }),
+            ImageFormat::Yuv420 => Image::Yuv420(ImageYuv420 {
+                header: header.clone(),
+                width,
+                height,
+                data,
+            }),
+            ImageFormat::Nv12 => Image::Nv12(ImageNv12 {
+                header: header.clone(),
+                width,
+                height,
+                data,
+            }),
+            ImageFormat::Gray8 => Image::Gray8(ImageGray8 {
+                header: header.clone(),
+                width,
+                height,
+                data,
+            }),
+            ImageFormat::Mjpeg => Image::Jpeg(ImageJPEG {
+                header: header.clone(),
+                width,
+                height,
+                data,
+            }),
+        };
+        ImageRawAny {
+            header,
+            image: Some(image),
+        }
+    }
+}
+
+/// Whether to publish raw decoded frames or compressed packets.
+enum OutputMode {
+    /// Decode to the configured `IMAGE_FORMAT` and publish on `IMAGE_RAW` (the default).
+    Raw,
+    /// Re-mux or re-encode and publish compressed packets on `IMAGE_COMPRESSED`.
+    Encoded,
+}
+
+impl Clone for OutputMode {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for OutputMode {}
+
+impl OutputMode {
+    fn from_str(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "encoded" => OutputMode::Encoded,
+            _ => OutputMode::Raw,
+        }
+    }
+}
+
+/// Target codec for the encoder stage.
+#[derive(Clone, Copy)]
+pub enum TranscodeCodec {
+    /// Bitstream copy — no re-encode (`-c:v copy`).
+    Copy,
+    H264,
+    Vp9,
+}
+
+impl TranscodeCodec {
+    fn from_str(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "h264" => TranscodeCodec::H264,
+            "vp9" => TranscodeCodec::Vp9,
+            _ => TranscodeCodec::Copy,
+        }
+    }
+}
+
+/// Per-camera encoder pipeline, analogous to the raw CSV config fields.
+#[derive(Clone)]
+pub struct TranscodeConfig {
+    pub codec: TranscodeCodec,
+    /// Target video bitrate in kbit/s (ignored for `Copy`).
+    pub bitrate_kbps: Option<u32>,
+    /// Downscale so the output is at most this wide, preserving aspect ratio.
+    pub max_width: Option<u32>,
+    /// Keyframe interval in frames.
+    pub gop: Option<u32>,
 }
 
 /// Parses the RTSP URL into `/camera/<ip>/<path>`
@@ -54,38 +259,239 @@ fn format_entity_path(rtsp_url: &str) -> String {
     }
 }
 
-/// Spawns a blocking thread to run FFmpeg and decode frames in the selected format.
-async fn spawn_ffmpeg_reader(
+/// Reset the backoff once a session has stayed up this long with frames.
+const RECONNECT_STABLE_SECS: u64 = 30;
+
+/// Shared per-camera liveness so downstream consumers (and the supervisor) can
+/// tell a stalled stream from a merely low-FPS one.
+#[derive(Default)]
+pub struct CameraLiveness {
+    /// Unix-epoch milliseconds of the most recent decoded frame (0 until one).
+    last_frame_unix_ms: AtomicU64,
+    /// Total frames decoded across all sessions for this camera.
+    frames: AtomicU64,
+}
+
+impl CameraLiveness {
+    /// Records that a frame was just decoded.
+    pub fn mark_frame(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.last_frame_unix_ms.store(now, Ordering::Relaxed);
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    /// Unix-epoch milliseconds of the most recent decoded frame (0 until one
+    /// has been decoded). Downstream consumers compare this against the wall
+    /// clock to tell a stalled camera from a merely low-FPS one.
+    pub fn last_frame_unix_ms(&self) -> u64 {
+        self.last_frame_unix_ms.load(Ordering::Relaxed)
+    }
+}
+
+/// Exponential backoff with jitter for restarting a dropped camera session.
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(initial_ms: u64, max_ms: u64) -> Self {
+        let initial = Duration::from_millis(initial_ms.max(1));
+        Self {
+            initial,
+            max: Duration::from_millis(max_ms.max(initial_ms)),
+            current: initial,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+
+    /// Returns the next delay (with ±25% jitter) and doubles the base, capped.
+    fn next_delay(&mut self) -> Duration {
+        let base = self.current;
+        self.current = (self.current * 2).min(self.max);
+        // Derive jitter from the clock's sub-millisecond tail to avoid a `rand`
+        // dependency while still spreading reconnects across many cameras.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let frac = (nanos % 1000) as f64 / 1000.0; // 0.0..1.0
+        // Scale by 0.75x..1.25x (±25% jitter). `mul_f64` panics on a negative
+        // multiplier, so keep the factor strictly positive rather than forming
+        // a signed jitter `Duration`.
+        base.mul_f64(0.75 + 0.5 * frac).min(self.max)
+    }
+}
+
+/// Drives one camera forever, restarting its backend session with exponential
+/// backoff whenever it terminates. Keeps the `watch`/[`FrameSender`] plumbing
+/// in `main` unchanged — only the lifecycle around a session lives here.
+async fn supervise(
     rtsp_url: String,
     stream_index: u32,
     sender: FrameSender,
     camera_idx: usize,
     image_format: ImageFormat,
-) -> Result<()> {
+    backend: RtspBackend,
+    transport: RtspTransport,
+    stream_info: StreamInfo,
+    liveness: Arc<CameraLiveness>,
+    initial_backoff_ms: u64,
+    max_backoff_ms: u64,
+    recording_config: RecordingConfig,
+    recording_events: UnboundedSender<RecordingFinished>,
+    output_mode: OutputMode,
+    transcode: TranscodeConfig,
+    compressed_tx: UnboundedSender<CompressedFrame>,
+) {
+    let mut backoff = Backoff::new(initial_backoff_ms, max_backoff_ms);
     let entity_path = format_entity_path(&rtsp_url);
 
-    task::spawn_blocking(move || {
-        let pix_fmt = match image_format {
-            ImageFormat::Rgb888 => "rgb24",
-            ImageFormat::Yuv420 => "yuv420p",
-        };
+    loop {
+        let started = SystemTime::now();
+        let frames_before = liveness.frames();
+        let mut recorder = Recorder::new(
+            recording_config.clone(),
+            entity_path.clone(),
+            image_format,
+            recording_events.clone(),
+        );
+
+        match (output_mode, backend) {
+            (OutputMode::Encoded, _) => {
+                let url = rtsp_url.clone();
+                let transcode = transcode.clone();
+                let stream_info = stream_info.clone();
+                let liveness = liveness.clone();
+                let sink = compressed_tx.clone();
+                let _ = task::spawn_blocking(move || {
+                    transcode::run_ffmpeg_encoded_session(
+                        &url,
+                        camera_idx,
+                        transport,
+                        &transcode,
+                        &stream_info,
+                        &liveness,
+                        &sink,
+                    )
+                })
+                .await;
+            }
+            (OutputMode::Raw, RtspBackend::Ffmpeg) => {
+                let sender = sender.clone();
+                let url = rtsp_url.clone();
+                let liveness = liveness.clone();
+                let stream_info = stream_info.clone();
+                recorder = task::spawn_blocking(move || {
+                    run_ffmpeg_session(
+                        &url,
+                        stream_index,
+                        &sender,
+                        camera_idx,
+                        image_format,
+                        transport,
+                        &stream_info,
+                        &liveness,
+                        &mut recorder,
+                    );
+                    recorder
+                })
+                .await
+                .expect("ffmpeg session task panicked");
+            }
+            (OutputMode::Raw, RtspBackend::Retina) => {
+                if let Err(e) = retina_backend::run_retina_session(
+                    &rtsp_url,
+                    stream_index,
+                    &sender,
+                    camera_idx,
+                    image_format,
+                    transport,
+                    &stream_info,
+                    &liveness,
+                    &mut recorder,
+                )
+                .await
+                {
+                    error!("[camera {camera_idx}] Retina session ended: {e}");
+                }
+            }
+        }
+
+        recorder.close();
+
+        let ran_for = started.elapsed().unwrap_or_default();
+        let produced = liveness.frames() > frames_before;
+        if produced && ran_for >= Duration::from_secs(RECONNECT_STABLE_SECS) {
+            backoff.reset();
+        }
+
+        let delay = backoff.next_delay();
+        warn!(
+            "[camera {camera_idx}] session ended after {:?} ({} frames); reconnecting in {:?}",
+            ran_for,
+            liveness.frames() - frames_before,
+            delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Runs a single FFmpeg session to completion, decoding frames in the selected
+/// format. Returns when ffmpeg exits, the event iterator ends, or the frame
+/// channel is dropped; the supervisor is responsible for restarting it.
+fn run_ffmpeg_session(
+    rtsp_url: &str,
+    stream_index: u32,
+    sender: &FrameSender,
+    camera_idx: usize,
+    image_format: ImageFormat,
+    transport: RtspTransport,
+    stream_info: &StreamInfo,
+    liveness: &CameraLiveness,
+    recorder: &mut Recorder,
+) {
+    let entity_path = format_entity_path(rtsp_url);
+
+    {
+        let pix_fmt = image_format.pix_fmt();
+        // MJPEG is emitted as compressed JPEG frames; every other format is a
+        // raw pixel plane.
+        let output_format = if image_format.is_mjpeg() { "mjpeg" } else { "rawvideo" };
 
         let mut child = FfmpegCommand::new()
             .args([
-                "-rtsp_transport", "tcp",
+                "-rtsp_transport", transport.ffmpeg_flag(),
                 "-timeout", "5000000",
                 "-allowed_media_types", "video",
             ])
-            .input(&rtsp_url)
+            .input(rtsp_url)
             .fps_mode("passthrough")
-            .format("rawvideo")
+            .format(output_format)
             .pix_fmt(pix_fmt)
             .pipe_stdout()
             .spawn()
             .expect(&format!("Failed to spawn ffmpeg (camera {camera_idx})"));
 
+        // MJPEG is delivered as raw `OutputChunk` bytes (ffmpeg_sidecar only
+        // synthesizes `OutputFrame` for rawvideo), so reassemble whole JPEGs
+        // from the pipe stream ourselves.
+        let mut jpeg = JpegReassembler::new();
+        let mut jpeg_frames: Vec<Vec<u8>> = Vec::new();
+
         if let Ok(iter) = child.iter() {
-            for event in iter {
+            'events: for event in iter {
                 match event {
                     FfmpegEvent::ParsedVersion(v) => {
                         info!("[camera {camera_idx}] Parsed FFmpeg version: {:?}", v);
@@ -154,40 +560,49 @@ async fn spawn_ffmpeg_reader(
                             reference_id: 0,
                             entity_path: entity_path.clone(),
                         });
-                        let image_any = match image_format {
-                            ImageFormat::Rgb888 => ImageRawAny {
-                                header: header.clone(),
-                                image: Some(make87_messages::image::uncompressed::image_raw_any::Image::Rgb888(
-                                    ImageRgb888 {
-                                        header,
-                                        width: frame.width,
-                                        height: frame.height,
-                                        data: frame.data,
-                                    }
-                                )),
-                            },
-                            ImageFormat::Yuv420 => {
-                                ImageRawAny {
-                                    header: header.clone(),
-                                    image: Some(make87_messages::image::uncompressed::image_raw_any::Image::Yuv420(
-                                        ImageYuv420 {
-                                            header,
-                                            width: frame.width,
-                                            height: frame.height,
-                                            data: frame.data
-                                        }
-                                    )),
-                                }
-                            }
-                        };
+                        // make87's `Header` carries no dimension or fps fields,
+                        // so the probed `StreamInfo` can't be attached there;
+                        // instead we seed the image message's own width/height
+                        // with the authoritative probe result whenever ffmpeg
+                        // reports none, and prefer the frame's values otherwise.
+                        // There is no message field to carry `fps`.
+                        let width = if frame.width == 0 { stream_info.width } else { frame.width };
+                        let height = if frame.height == 0 { stream_info.height } else { frame.height };
+
+                        recorder.on_frame(width, height, &frame.data);
+                        let image_any = image_format.wrap(header, width, height, frame.data);
 
+                        liveness.mark_frame();
                         if sender.send(Some(image_any)).is_err() {
                             error!("[camera {camera_idx}] Channel closed, stopping reader thread");
                             break;
                         }
                     }
                     FfmpegEvent::OutputChunk(chunk) => {
-                        trace!("[camera {camera_idx}] Received output chunk ({} bytes)", chunk.len());
+                        if !image_format.is_mjpeg() {
+                            trace!("[camera {camera_idx}] Received output chunk ({} bytes)", chunk.len());
+                            continue;
+                        }
+                        jpeg.push(&chunk, &mut jpeg_frames);
+                        for data in jpeg_frames.drain(..) {
+                            let timestamp = Timestamp::get_current_time().into();
+                            let header = Some(Header {
+                                timestamp: Some(timestamp),
+                                reference_id: 0,
+                                entity_path: entity_path.clone(),
+                            });
+                            // MJPEG carries no per-frame dimensions on the wire;
+                            // use the authoritative probe result.
+                            let (width, height) = (stream_info.width, stream_info.height);
+                            recorder.on_frame(width, height, &data);
+                            let image_any = image_format.wrap(header, width, height, data);
+
+                            liveness.mark_frame();
+                            if sender.send(Some(image_any)).is_err() {
+                                error!("[camera {camera_idx}] Channel closed, stopping reader thread");
+                                break 'events;
+                            }
+                        }
                     }
                     FfmpegEvent::Done => {
                         info!("[camera {camera_idx}] FFmpeg processing done");
@@ -195,9 +610,57 @@ async fn spawn_ffmpeg_reader(
                 }
             }
         }
-    });
+    }
+}
 
-    Ok(())
+/// Reassembles whole JPEG frames from a raw MJPEG byte stream that arrives in
+/// arbitrary pipe-sized chunks, splitting on SOI (`FF D8`) / EOI (`FF D9`)
+/// markers so each published frame is one complete image.
+struct JpegReassembler {
+    buf: Vec<u8>,
+}
+
+impl JpegReassembler {
+    fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Feeds a chunk and appends any now-complete JPEG frames to `out`.
+    fn push(&mut self, chunk: &[u8], out: &mut Vec<Vec<u8>>) {
+        self.buf.extend_from_slice(chunk);
+        loop {
+            let Some(soi) = find_marker(&self.buf, 0, 0xD8) else {
+                // No start-of-image yet; keep only a trailing `FF` in case the
+                // marker is split across chunks, and stop growing the buffer.
+                let tail = self.buf.last().copied().filter(|b| *b == 0xFF);
+                self.buf.clear();
+                if let Some(b) = tail {
+                    self.buf.push(b);
+                }
+                break;
+            };
+            if soi > 0 {
+                self.buf.drain(..soi);
+            }
+            let Some(eoi) = find_marker(&self.buf, 2, 0xD9) else {
+                break;
+            };
+            let frame: Vec<u8> = self.buf.drain(..eoi + 2).collect();
+            out.push(frame);
+        }
+    }
+}
+
+/// Index of the first `FF <code>` marker at or after `from`.
+fn find_marker(data: &[u8], from: usize, code: u8) -> Option<usize> {
+    let mut i = from;
+    while i + 1 < data.len() {
+        if data[i] == 0xFF && data[i + 1] == code {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
 }
 
 fn parse_csv<T: std::str::FromStr>(input: &str, field: &str) -> Result<Vec<T>, anyhow::Error>
@@ -219,6 +682,32 @@ fn load_camera_config() -> Result<CameraConfig, anyhow::Error> {
     let uri_suffix = make87::get_config_value("CAMERA_URI_SUFFIX").unwrap_or_default();
     let stream_index = make87::get_config_value("STREAM_INDEX").unwrap_or_else(|| "0".to_string());
 
+    let backend = make87::get_config_value("RTSP_BACKEND")
+        .map(|s| RtspBackend::from_str(&s))
+        .unwrap_or(RtspBackend::Ffmpeg);
+    let transport = make87::get_config_value("RTSP_TRANSPORT")
+        .map(|s| RtspTransport::from_str(&s))
+        .unwrap_or(RtspTransport::Tcp);
+
+    let reconnect_initial_backoff_ms = make87::get_config_value("RECONNECT_INITIAL_BACKOFF_MS")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(500);
+    let reconnect_max_backoff_ms = make87::get_config_value("RECONNECT_MAX_BACKOFF_MS")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(30_000);
+
+    let recording = RecordingConfig {
+        dir: make87::get_config_value("RECORD_DIR").map(std::path::PathBuf::from),
+        motion_threshold: make87::get_config_value("MOTION_THRESHOLD")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(8.0),
+        stop_timeout: std::time::Duration::from_secs(
+            make87::get_config_value("MOTION_STOP_TIMEOUT_SECS")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(3),
+        ),
+    };
+
     let usernames = parse_csv::<String>(&username, "CAMERA_USERNAME")?;
     let passwords = parse_csv::<String>(&password, "CAMERA_PASSWORD")?;
     let ips = parse_csv::<String>(&ip, "CAMERA_IP")?;
@@ -249,6 +738,26 @@ fn load_camera_config() -> Result<CameraConfig, anyhow::Error> {
         ));
     }
 
+    // Encoder pipeline, parsed per-camera and broadcast from a single value.
+    let output_mode = make87::get_config_value("OUTPUT_MODE")
+        .map(|s| OutputMode::from_str(&s))
+        .unwrap_or(OutputMode::Raw);
+    let codecs = broadcast_field("TRANSCODE_CODEC", expected)?;
+    let bitrates = broadcast_field("TRANSCODE_BITRATE", expected)?;
+    let max_widths = broadcast_field("TRANSCODE_MAX_WIDTH", expected)?;
+    let gops = broadcast_field("TRANSCODE_GOP", expected)?;
+    let transcode = (0..expected)
+        .map(|i| TranscodeConfig {
+            codec: codecs[i]
+                .as_deref()
+                .map(TranscodeCodec::from_str)
+                .unwrap_or(TranscodeCodec::Copy),
+            bitrate_kbps: bitrates[i].as_deref().and_then(|s| s.parse().ok()),
+            max_width: max_widths[i].as_deref().and_then(|s| s.parse().ok()),
+            gop: gops[i].as_deref().and_then(|s| s.parse().ok()),
+        })
+        .collect();
+
     Ok(CameraConfig {
         username: usernames,
         password: passwords,
@@ -256,9 +765,168 @@ fn load_camera_config() -> Result<CameraConfig, anyhow::Error> {
         port: ports,
         uri_suffix: uri_suffixes,
         stream_index: stream_indices,
+        backend,
+        transport,
+        reconnect_initial_backoff_ms,
+        reconnect_max_backoff_ms,
+        recording,
+        output_mode,
+        transcode,
     })
 }
 
+/// Reads an optional comma-separated config value and normalises it to exactly
+/// `n` entries: absent → all `None`, a single value → broadcast to every
+/// camera, otherwise the count must match the other camera fields.
+fn broadcast_field(name: &str, n: usize) -> Result<Vec<Option<String>>, anyhow::Error> {
+    let Some(raw) = make87::get_config_value(name) else {
+        return Ok(vec![None; n]);
+    };
+    let values: Vec<Option<String>> = raw
+        .split(',')
+        .map(|s| {
+            let t = s.trim();
+            if t.is_empty() { None } else { Some(t.to_string()) }
+        })
+        .collect();
+    match values.len() {
+        1 => Ok(vec![values.into_iter().next().unwrap(); n]),
+        len if len == n => Ok(values),
+        len => Err(anyhow::anyhow!(
+            "{name} has {len} values but there are {n} camera(s)"
+        )),
+    }
+}
+
+/// Escapes a string for safe interpolation into a JSON string literal, so a
+/// backslash (e.g. a Windows `RECORD_DIR`) or quote in a path can't emit
+/// invalid JSON to downstream consumers.
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Fans recording events in from all cameras and republishes them as JSON on
+/// the `RECORDING_FINISHED` topic. Falls back to logging when the topic is not
+/// wired up so the channel never backs up.
+fn spawn_recording_event_publisher(mut rx: mpsc::UnboundedReceiver<RecordingFinished>) {
+    let publisher = make87::resolve_topic_name("RECORDING_FINISHED")
+        .and_then(|resolved| make87::get_publisher::<String>(resolved));
+
+    tokio::spawn(async move {
+        while let Some(ev) = rx.recv().await {
+            let payload = format!(
+                "{{\"file_path\":\"{}\",\"entity_path\":\"{}\",\"start_unix_ms\":{},\"end_unix_ms\":{}}}",
+                json_escape(&ev.file_path),
+                json_escape(&ev.entity_path),
+                ev.start_unix_ms,
+                ev.end_unix_ms
+            );
+            match &publisher {
+                Some(publisher) => {
+                    if let Err(e) = publisher.publish_async(&payload).await {
+                        eprintln!("Failed to publish recording event: {e}");
+                    }
+                }
+                None => info!("Recording finished (no topic): {payload}"),
+            }
+        }
+    });
+}
+
+/// Fans compressed packets in from all cameras and publishes them on the
+/// `IMAGE_COMPRESSED` topic. The keyframe flag is carried in `Header.reference_id`
+/// (1 = keyframe, 0 = delta) so consumers can find a resync point.
+fn spawn_compressed_publisher(mut rx: mpsc::UnboundedReceiver<CompressedFrame>) {
+    use make87_messages::image::compressed::{
+        image_compressed_any::Image, ImageCompressedAny, ImageH264, ImageVp9,
+    };
+
+    let publisher = make87::resolve_topic_name("IMAGE_COMPRESSED")
+        .and_then(|resolved| make87::get_publisher::<ImageCompressedAny>(resolved));
+
+    tokio::spawn(async move {
+        let Some(publisher) = publisher else {
+            warn!("IMAGE_COMPRESSED topic not resolved; dropping compressed frames");
+            while rx.recv().await.is_some() {}
+            return;
+        };
+
+        while let Some(frame) = rx.recv().await {
+            let header = Some(Header {
+                timestamp: Some(Timestamp::get_current_time().into()),
+                reference_id: frame.keyframe as u32,
+                entity_path: frame.entity_path,
+            });
+            let image = match frame.codec {
+                TranscodeCodec::Vp9 => Image::Vp9(ImageVp9 {
+                    header: header.clone(),
+                    width: frame.width,
+                    height: frame.height,
+                    data: frame.data,
+                }),
+                // `Copy` passthrough preserves the source H.264 bitstream.
+                TranscodeCodec::H264 | TranscodeCodec::Copy => Image::H264(ImageH264 {
+                    header: header.clone(),
+                    width: frame.width,
+                    height: frame.height,
+                    data: frame.data,
+                }),
+            };
+            let message = ImageCompressedAny {
+                header,
+                image: Some(image),
+            };
+            if let Err(e) = publisher.publish_async(&message).await {
+                eprintln!("Failed to publish compressed frame: {e}");
+            }
+        }
+    });
+}
+
+/// Periodically publishes each camera's last-frame timestamp on the
+/// `CAMERA_LIVENESS` topic as JSON so downstream consumers can tell a stalled
+/// stream from a merely low-FPS one. Falls back to logging when the topic is
+/// not wired up.
+fn spawn_liveness_publisher(cameras: Vec<(String, Arc<CameraLiveness>)>) {
+    let publisher = make87::resolve_topic_name("CAMERA_LIVENESS")
+        .and_then(|resolved| make87::get_publisher::<String>(resolved));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            ticker.tick().await;
+            for (entity_path, liveness) in &cameras {
+                let payload = format!(
+                    "{{\"entity_path\":\"{}\",\"last_frame_unix_ms\":{},\"frames\":{}}}",
+                    json_escape(entity_path),
+                    liveness.last_frame_unix_ms(),
+                    liveness.frames()
+                );
+                match &publisher {
+                    Some(publisher) => {
+                        if let Err(e) = publisher.publish_async(&payload).await {
+                            eprintln!("Failed to publish liveness: {e}");
+                        }
+                    }
+                    None => debug!("Camera liveness (no topic): {payload}"),
+                }
+            }
+        }
+    });
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     make87::initialize();
@@ -269,11 +937,33 @@ async fn main() -> Result<()> {
         .map(|s| ImageFormat::from_str(&s))
         .unwrap_or(ImageFormat::Rgb888);
 
+    // MJPEG is an ffmpeg-only compressed passthrough; the in-process retina
+    // decoder only emits planar frames, so reject the combination rather than
+    // publishing raw YUV mislabeled as JPEG.
+    if matches!(config.backend, RtspBackend::Retina) && image_format.is_mjpeg() {
+        return Err(anyhow::anyhow!(
+            "IMAGE_FORMAT=MJPEG is not supported by RTSP_BACKEND=retina; use the ffmpeg backend"
+        ));
+    }
+
     let publisher = make87::resolve_topic_name("IMAGE_RAW")
         .and_then(|resolved| make87::get_publisher::<ImageRawAny>(resolved))
         .expect("Failed to resolve or create publisher");
 
+    // "Recording finished" events are fanned in from every camera and published
+    // on their own topic.
+    let (recording_events, recording_rx) = mpsc::unbounded_channel::<RecordingFinished>();
+    spawn_recording_event_publisher(recording_rx);
+
+    // Compressed packets (encoded output mode) are fanned in and published on
+    // their own topic, keeping the raw `IMAGE_RAW` plumbing untouched.
+    let (compressed_tx, compressed_rx) = mpsc::unbounded_channel::<CompressedFrame>();
+    spawn_compressed_publisher(compressed_rx);
+
     let mut receivers = Vec::new();
+    // Retained (with each camera's entity path) so the liveness publisher can
+    // report every camera's last-frame timestamp.
+    let mut liveness_states: Vec<(String, Arc<CameraLiveness>)> = Vec::new();
 
     for idx in 0..config.ip.len() {
         // Compose RTSP URL with optional username/password
@@ -298,18 +988,58 @@ async fn main() -> Result<()> {
             path = config.uri_suffix[idx]
         );
 
+        let stream_index = config.stream_index[idx];
+
+        // Discover-then-process: fail fast on an unreachable URL or a missing
+        // stream index before spawning a long-running decoder. The retina
+        // backend probes in-process so it needs no ffmpeg binary at startup.
+        let streams = match config.backend {
+            RtspBackend::Retina => retina_backend::probe_retina(&rtsp_url).await?,
+            RtspBackend::Ffmpeg => {
+                let probe_url = rtsp_url.clone();
+                let transport = config.transport;
+                task::spawn_blocking(move || probe::probe_camera(&probe_url, transport))
+                    .await
+                    .expect("probe task panicked")?
+            }
+        };
+        let stream_info = probe::select_stream(&streams, stream_index)?;
+        info!(
+            "[camera {idx}] probed {} {}x{} @ {:.2}fps ({})",
+            stream_info.codec,
+            stream_info.width,
+            stream_info.height,
+            stream_info.fps,
+            stream_info.pix_fmt
+        );
+
         let (sender, receiver) = watch::channel(None);
         receivers.push(receiver);
-        let stream_index = config.stream_index[idx];
-        tokio::spawn(spawn_ffmpeg_reader(
+        let liveness = Arc::new(CameraLiveness::default());
+        liveness_states.push((format_entity_path(&rtsp_url), liveness.clone()));
+        tokio::spawn(supervise(
             rtsp_url.clone(),
             stream_index,
             sender,
             idx,
             image_format.clone(),
+            config.backend,
+            config.transport,
+            stream_info,
+            liveness,
+            config.reconnect_initial_backoff_ms,
+            config.reconnect_max_backoff_ms,
+            config.recording.clone(),
+            recording_events.clone(),
+            config.output_mode,
+            config.transcode[idx].clone(),
+            compressed_tx.clone(),
         ));
     }
 
+    info!("Supervising {} camera(s)", liveness_states.len());
+    spawn_liveness_publisher(liveness_states);
+
     // Wrap all receivers as streams and select over them
     let mut select_all = SelectAll::new();
     for receiver in receivers {
@@ -0,0 +1,98 @@
+//! Pre-flight RTSP stream discovery.
+//!
+//! Before committing to a pixel format and spawning a long-running decode, we
+//! run a short ffprobe-style pass that reports the codec, native resolution,
+//! pixel format and frame rate of each video stream. This mirrors the
+//! discover-then-process pattern: `main` validates the requested
+//! `STREAM_INDEX` against the probe result and fails fast with a clear error
+//! rather than spawning a decoder against a dead endpoint or a missing stream.
+
+use anyhow::{anyhow, Result};
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::FfmpegEvent;
+
+use crate::RtspTransport;
+
+/// Authoritative metadata for one discovered video stream.
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    /// Index among the input's video streams (matches `STREAM_INDEX`).
+    pub stream_index: u32,
+    /// Codec / container format name as reported by ffmpeg (e.g. `h264`).
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    pub pix_fmt: String,
+    /// Native frame rate. Logged at startup for operators; the make87 message
+    /// set has no field to carry it through to consumers.
+    pub fps: f32,
+}
+
+/// Probes `rtsp_url` and returns one [`StreamInfo`] per video stream, in order.
+///
+/// Runs a zero-length ffmpeg pass (`-t 0 -f null`) purely to collect the parsed
+/// input-stream metadata; it never decodes a frame.
+pub fn probe_camera(rtsp_url: &str, transport: RtspTransport) -> Result<Vec<StreamInfo>> {
+    let mut child = FfmpegCommand::new()
+        .args([
+            "-rtsp_transport", transport.ffmpeg_flag(),
+            "-timeout", "5000000",
+            "-allowed_media_types", "video",
+        ])
+        .input(rtsp_url)
+        .args(["-t", "0", "-f", "null", "-"])
+        .spawn()
+        .map_err(|e| anyhow!("failed to spawn ffprobe pass: {e}"))?;
+
+    let mut streams = Vec::new();
+    let mut fatal: Option<String> = None;
+
+    let iter = child
+        .iter()
+        .map_err(|e| anyhow!("failed to read ffprobe output: {e}"))?;
+    for event in iter {
+        match event {
+            FfmpegEvent::ParsedInputStream(stream) if stream.stream_type == "Video" => {
+                streams.push(StreamInfo {
+                    stream_index: streams.len() as u32,
+                    codec: stream.format.clone(),
+                    width: stream.width,
+                    height: stream.height,
+                    pix_fmt: stream.pix_fmt.clone(),
+                    fps: stream.fps,
+                });
+            }
+            FfmpegEvent::Log(ffmpeg_sidecar::event::LogLevel::Fatal, msg) => {
+                fatal = Some(msg);
+            }
+            FfmpegEvent::Error(err) => {
+                fatal = Some(err);
+            }
+            _ => {}
+        }
+    }
+
+    if streams.is_empty() {
+        return Err(anyhow!(
+            "no video stream found at {rtsp_url}{}",
+            fatal.map(|m| format!(": {m}")).unwrap_or_default()
+        ));
+    }
+
+    Ok(streams)
+}
+
+/// Resolves the configured `stream_index` against a probe result, returning the
+/// authoritative [`StreamInfo`] or a clear out-of-range error.
+pub fn select_stream(streams: &[StreamInfo], stream_index: u32) -> Result<StreamInfo> {
+    streams
+        .iter()
+        .find(|s| s.stream_index == stream_index)
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "STREAM_INDEX {stream_index} out of range: only {} video stream(s) present",
+                streams.len()
+            )
+        })
+}
@@ -0,0 +1,323 @@
+//! NVR-style motion-triggered segment recording.
+//!
+//! A [`Recorder`] taps the decoded frames a camera session produces, runs cheap
+//! frame-difference motion detection on a downscaled grayscale thumbnail, and
+//! while there is activity muxes the incoming frames into timestamped MP4
+//! segments on disk. When activity ceases for [`RecordingConfig::stop_timeout`]
+//! the segment is finalized and a [`RecordingFinished`] event is emitted so
+//! downstream consumers can react to the new file.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ffmpeg_sidecar::command::FfmpegCommand;
+use log::{error, info, warn};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::ImageFormat;
+
+/// Edge length of the grayscale thumbnail used for motion detection.
+const THUMB_SIZE: usize = 32;
+
+/// Frames buffered to the segment writer thread before we start dropping, so
+/// recording backpressure can never stall live decode or `IMAGE_RAW` publishing.
+const SEGMENT_QUEUE_DEPTH: usize = 8;
+
+/// Per-camera recording configuration, shared across reconnects.
+#[derive(Clone)]
+pub struct RecordingConfig {
+    /// Directory segments are written to; recording is disabled when `None`.
+    pub dir: Option<PathBuf>,
+    /// Mean absolute per-pixel difference (0-255) above which a frame counts as
+    /// activity.
+    pub motion_threshold: f32,
+    /// How long activity must be absent before a segment is finalized.
+    pub stop_timeout: Duration,
+}
+
+impl RecordingConfig {
+    fn enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+}
+
+/// Emitted when a motion-triggered segment has been written and closed.
+#[derive(Clone, Debug)]
+pub struct RecordingFinished {
+    pub file_path: String,
+    pub entity_path: String,
+    pub start_unix_ms: u64,
+    pub end_unix_ms: u64,
+}
+
+/// Drives motion detection and segment recording for a single camera.
+pub struct Recorder {
+    config: RecordingConfig,
+    entity_path: String,
+    image_format: ImageFormat,
+    events: UnboundedSender<RecordingFinished>,
+    prev_thumb: Option<[u8; THUMB_SIZE * THUMB_SIZE]>,
+    active: Option<Segment>,
+    /// Whether the one-shot "MJPEG unsupported" warning has been logged.
+    mjpeg_warned: bool,
+}
+
+/// State for the in-progress MP4 segment. The encoder and its blocking stdin
+/// live on a dedicated writer thread; frames reach it over a bounded channel so
+/// the live decode thread never blocks on pipe backpressure.
+struct Segment {
+    frames: SyncSender<Vec<u8>>,
+    writer: Option<JoinHandle<()>>,
+    path: PathBuf,
+    start_unix_ms: u64,
+    last_motion_unix_ms: u64,
+    /// Frames dropped because the writer queue was full.
+    dropped: u64,
+}
+
+impl Recorder {
+    pub fn new(
+        config: RecordingConfig,
+        entity_path: String,
+        image_format: ImageFormat,
+        events: UnboundedSender<RecordingFinished>,
+    ) -> Self {
+        Self {
+            config,
+            entity_path,
+            image_format,
+            events,
+            prev_thumb: None,
+            active: None,
+            mjpeg_warned: false,
+        }
+    }
+
+    /// Feeds one decoded frame through motion detection and, when recording is
+    /// active, into the current segment. Cheap no-op when recording is disabled.
+    pub fn on_frame(&mut self, width: u32, height: u32, data: &[u8]) {
+        if !self.config.enabled() {
+            return;
+        }
+
+        // Compressed JPEG frames can't be sampled for motion, nor fed to the
+        // rawvideo segment encoder without corrupting the MP4, so motion
+        // recording is unsupported for MJPEG. Warn once rather than silently
+        // doing nothing.
+        if self.image_format.is_mjpeg() {
+            if !self.mjpeg_warned {
+                warn!(
+                    "[{}] motion recording is not supported for MJPEG; disabling for this camera",
+                    self.entity_path
+                );
+                self.mjpeg_warned = true;
+            }
+            return;
+        }
+
+        let now = now_unix_ms();
+        let thumb = thumbnail_gray(data, width, height, self.image_format);
+        let motion = match self.prev_thumb {
+            Some(prev) => mean_abs_diff(&prev, &thumb) > self.config.motion_threshold,
+            None => false,
+        };
+        self.prev_thumb = Some(thumb);
+
+        if motion {
+            if self.active.is_none() {
+                self.start_segment(width, height, now);
+            }
+            if let Some(seg) = self.active.as_mut() {
+                seg.last_motion_unix_ms = now;
+            }
+        }
+
+        if let Some(seg) = self.active.as_mut() {
+            // Non-blocking hand-off to the writer thread; drop on overflow so a
+            // slow encoder can never throttle live decoding or publishing.
+            if seg.frames.try_send(data.to_vec()).is_err() {
+                seg.dropped = seg.dropped.saturating_add(1);
+            }
+            // Trailing-timeout debounce: stop once motion has been quiet long enough.
+            if now.saturating_sub(seg.last_motion_unix_ms)
+                >= self.config.stop_timeout.as_millis() as u64
+            {
+                self.finalize(now);
+            }
+        }
+    }
+
+    /// Closes any in-progress segment (e.g. when a session is torn down).
+    pub fn close(&mut self) {
+        if self.active.is_some() {
+            self.finalize(now_unix_ms());
+        }
+    }
+
+    fn start_segment(&mut self, width: u32, height: u32, now: u64) {
+        let Some(dir) = self.config.dir.clone() else {
+            return;
+        };
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            error!("[{}] cannot create record dir {:?}: {e}", self.entity_path, dir);
+            return;
+        }
+        let path = dir.join(format!("{}_{}.mp4", sanitize(&self.entity_path), now));
+
+        let size = format!("{}x{}", width, height);
+        let child = FfmpegCommand::new()
+            .format("rawvideo")
+            .pix_fmt(self.image_format.pix_fmt())
+            .size(width, height)
+            .input("-")
+            .args(["-y", "-an"])
+            .output(path.to_string_lossy().as_ref())
+            .spawn();
+
+        match child {
+            Ok(mut child) => {
+                let (frames, rx) = sync_channel::<Vec<u8>>(SEGMENT_QUEUE_DEPTH);
+                let entity = self.entity_path.clone();
+                // The encoder's blocking stdin is driven from its own thread so
+                // pipe backpressure never reaches the decode thread.
+                let writer = std::thread::spawn(move || {
+                    let mut stdin = child.as_inner_mut().stdin.take();
+                    while let Ok(frame) = rx.recv() {
+                        if let Some(stdin) = stdin.as_mut() {
+                            if let Err(e) = stdin.write_all(&frame) {
+                                error!("[{entity}] failed writing segment frame: {e}");
+                                break;
+                            }
+                        }
+                    }
+                    // Dropping stdin signals EOF so ffmpeg flushes the moov atom.
+                    drop(stdin);
+                    if let Err(e) = child.wait() {
+                        warn!("[{entity}] segment encoder exited with error: {e}");
+                    }
+                });
+                info!("[{}] recording started -> {:?} ({size})", self.entity_path, path);
+                self.active = Some(Segment {
+                    frames,
+                    writer: Some(writer),
+                    path,
+                    start_unix_ms: now,
+                    last_motion_unix_ms: now,
+                    dropped: 0,
+                });
+            }
+            Err(e) => error!("[{}] failed to start segment encoder: {e}", self.entity_path),
+        }
+    }
+
+    fn finalize(&mut self, now: u64) {
+        let Some(seg) = self.active.take() else {
+            return;
+        };
+        let Segment {
+            frames,
+            writer,
+            path,
+            start_unix_ms,
+            dropped,
+            ..
+        } = seg;
+        // Closing the channel lets the writer thread flush and finalize the MP4.
+        drop(frames);
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        if dropped > 0 {
+            warn!(
+                "[{}] dropped {dropped} frame(s) under recording backpressure",
+                self.entity_path
+            );
+        }
+
+        let event = RecordingFinished {
+            file_path: path.to_string_lossy().into_owned(),
+            entity_path: self.entity_path.clone(),
+            start_unix_ms,
+            end_unix_ms: now,
+        };
+        info!(
+            "[{}] recording finished -> {} ({} ms)",
+            self.entity_path,
+            event.file_path,
+            event.end_unix_ms.saturating_sub(event.start_unix_ms)
+        );
+        if self.events.send(event).is_err() {
+            warn!("[{}] recording-event channel closed", self.entity_path);
+        }
+    }
+}
+
+fn now_unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Replaces path separators so an entity path is usable as a filename stem.
+fn sanitize(entity_path: &str) -> String {
+    entity_path
+        .trim_matches('/')
+        .replace(['/', ':'], "_")
+}
+
+/// Downscales a frame to a `THUMB_SIZE`×`THUMB_SIZE` grayscale thumbnail by
+/// nearest-neighbour sampling of the luma channel.
+fn thumbnail_gray(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+) -> [u8; THUMB_SIZE * THUMB_SIZE] {
+    let (w, h) = (width as usize, height as usize);
+    let mut thumb = [0u8; THUMB_SIZE * THUMB_SIZE];
+    if w == 0 || h == 0 {
+        return thumb;
+    }
+
+    let luma = |x: usize, y: usize| -> u8 {
+        match format {
+            // Y plane is stored first (YUV420/NV12) or is the whole frame (GRAY8).
+            ImageFormat::Yuv420 | ImageFormat::Nv12 | ImageFormat::Gray8 => {
+                data.get(y * w + x).copied().unwrap_or(0)
+            }
+            // Rec. 601 luma from packed RGB.
+            ImageFormat::Rgb888 => {
+                let o = (y * w + x) * 3;
+                let r = data.get(o).copied().unwrap_or(0) as u32;
+                let g = data.get(o + 1).copied().unwrap_or(0) as u32;
+                let b = data.get(o + 2).copied().unwrap_or(0) as u32;
+                ((77 * r + 150 * g + 29 * b) >> 8) as u8
+            }
+            // Compressed frames can't be sampled cheaply; treat as no motion.
+            ImageFormat::Mjpeg => 0,
+        }
+    };
+
+    for ty in 0..THUMB_SIZE {
+        for tx in 0..THUMB_SIZE {
+            let sx = tx * w / THUMB_SIZE;
+            let sy = ty * h / THUMB_SIZE;
+            thumb[ty * THUMB_SIZE + tx] = luma(sx, sy);
+        }
+    }
+    thumb
+}
+
+/// Mean absolute per-pixel difference between two equal-sized thumbnails.
+fn mean_abs_diff(a: &[u8], b: &[u8]) -> f32 {
+    let sum: u32 = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| x.abs_diff(*y) as u32)
+        .sum();
+    sum as f32 / a.len() as f32
+}
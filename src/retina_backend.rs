@@ -0,0 +1,387 @@
+//! Pure-Rust RTSP backend built on the `retina` crate.
+//!
+//! Unlike [`crate::run_ffmpeg_session`], this backend never spawns a child
+//! process: it opens the RTSP session in-process, depacketizes the H.264
+//! access units itself and decodes them to the same [`ImageRawAny`] the ffmpeg
+//! path publishes, so `main` stays agnostic to which backend produced a frame.
+//! Only H.264 is decoded: a stream in any other codec, H.265 included, ends the
+//! session with an error; such cameras are served by the ffmpeg backend
+//! (`RTSP_BACKEND=ffmpeg`).
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use log::{error, info, warn};
+use make87_messages::core::Header;
+use make87_messages::google::protobuf::Timestamp;
+use make87_messages::image::uncompressed::ImageRawAny;
+use retina::client::{PlayOptions, SessionOptions, SetupOptions, Transport};
+use retina::codec::{CodecItem, VideoParameters};
+use url::Url;
+
+use crate::{format_entity_path, CameraLiveness, FrameSender, ImageFormat, RtspTransport};
+
+impl RtspTransport {
+    /// The interleaved/UDP transport retina should negotiate.
+    fn retina_transport(&self) -> Transport {
+        match self {
+            RtspTransport::Tcp => Transport::Tcp(Default::default()),
+            RtspTransport::Udp => Transport::Udp(Default::default()),
+        }
+    }
+}
+
+/// Runs a single in-process RTSP session to completion, decoding frames in the
+/// selected format. Returns when the stream ends or errors; the supervisor in
+/// `main` restarts it, so the `watch`/[`FrameSender`] plumbing stays unchanged.
+pub async fn run_retina_session(
+    rtsp_url: &str,
+    stream_index: u32,
+    sender: &FrameSender,
+    camera_idx: usize,
+    image_format: ImageFormat,
+    transport: RtspTransport,
+    stream_info: &crate::probe::StreamInfo,
+    liveness: &CameraLiveness,
+    recorder: &mut crate::recording::Recorder,
+) -> Result<()> {
+    // MJPEG is an ffmpeg-only passthrough; the in-process decoder only emits
+    // planar frames, so refuse rather than publishing garbage. `main` rejects
+    // this combination at startup; this guards the backend directly too.
+    if image_format.is_mjpeg() {
+        return Err(anyhow!(
+            "[camera {camera_idx}] MJPEG is unsupported by the retina backend; use the ffmpeg backend"
+        ));
+    }
+
+    let entity_path = format_entity_path(rtsp_url);
+    let url = Url::parse(rtsp_url)
+        .map_err(|e| anyhow!("[camera {camera_idx}] invalid RTSP URL: {e}"))?;
+
+    let session_opts = SessionOptions::default().user_agent("rtsp-camera-driver-raw".to_owned());
+    let mut session = retina::client::Session::describe(url, session_opts).await?;
+
+    // Resolve the requested stream index to the Nth video stream.
+    let video_stream = session
+        .streams()
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.media() == "video")
+        .nth(stream_index as usize)
+        .map(|(i, _)| i)
+        .ok_or_else(|| anyhow!("[camera {camera_idx}] video stream {stream_index} not found"))?;
+
+    session
+        .setup(video_stream, SetupOptions::default().transport(transport.retina_transport()))
+        .await?;
+
+    // Fail fast — rather than log-spamming a decode error per frame forever —
+    // when the selected stream is a codec the in-process decoder can't handle.
+    // Returning `Err` lets the supervisor tear the session down; operators fall
+    // back with `RTSP_BACKEND=ffmpeg` as the module docs describe.
+    if let Some(retina::codec::ParametersRef::Video(v)) =
+        session.streams()[video_stream].parameters()
+    {
+        if !v.rfc6381_codec().starts_with("avc1") {
+            return Err(anyhow!(
+                "[camera {camera_idx}] stream {stream_index} codec {} is unsupported by the retina backend; use RTSP_BACKEND=ffmpeg",
+                v.rfc6381_codec()
+            ));
+        }
+    }
+
+    let mut demuxed = session
+        .play(PlayOptions::default())
+        .await?
+        .demuxed()?;
+
+    info!("[camera {camera_idx}] Retina session playing (stream {stream_index})");
+
+    let mut decoder = Decoder::new();
+
+    while let Some(item) = demuxed.next().await {
+        match item? {
+            CodecItem::VideoFrame(frame) => {
+                let params = match frame.stream().parameters() {
+                    Some(retina::codec::ParametersRef::Video(v)) => v,
+                    _ => continue,
+                };
+                let decoded = match decoder.decode(params, frame.data()) {
+                    Ok(Some(d)) => d,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("[camera {camera_idx}] decode error: {e}");
+                        continue;
+                    }
+                };
+
+                let timestamp = Timestamp::get_current_time().into();
+                let header = Some(Header {
+                    timestamp: Some(timestamp),
+                    reference_id: 0,
+                    entity_path: entity_path.to_string(),
+                });
+                let width = if decoded.width == 0 { stream_info.width } else { decoded.width };
+                let height = if decoded.height == 0 { stream_info.height } else { decoded.height };
+                let bytes = decoded.as_format(image_format);
+                recorder.on_frame(width, height, &bytes);
+                let image_any = image_format.wrap(header, width, height, bytes);
+
+                liveness.mark_frame();
+                if sender.send(Some(image_any)).is_err() {
+                    error!("[camera {camera_idx}] Channel closed, stopping Retina reader");
+                    break;
+                }
+            }
+            CodecItem::Rtcp(_) => {}
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Probes an RTSP endpoint in-process via retina's `DESCRIBE`, returning one
+/// [`StreamInfo`] per video stream — the same discover-then-process contract as
+/// [`crate::probe::probe_camera`] but without requiring the ffmpeg binary, so
+/// the retina path carries no ffmpeg dependency at startup.
+pub async fn probe_retina(rtsp_url: &str) -> Result<Vec<crate::probe::StreamInfo>> {
+    let url = Url::parse(rtsp_url).map_err(|e| anyhow!("invalid RTSP URL: {e}"))?;
+    let session_opts = SessionOptions::default().user_agent("rtsp-camera-driver-raw".to_owned());
+    let session = retina::client::Session::describe(url, session_opts).await?;
+
+    let mut infos = Vec::new();
+    for stream in session.streams().iter().filter(|s| s.media() == "video") {
+        let (width, height) = match stream.parameters() {
+            Some(retina::codec::ParametersRef::Video(v)) => v.pixel_dimensions(),
+            _ => (0, 0),
+        };
+        infos.push(crate::probe::StreamInfo {
+            stream_index: infos.len() as u32,
+            codec: stream.encoding_name().to_string(),
+            width,
+            height,
+            // retina's DESCRIBE exposes neither a decoded pixel format nor a
+            // reliable frame rate; both are only known once decoding starts.
+            pix_fmt: String::new(),
+            fps: 0.0,
+        });
+    }
+
+    if infos.is_empty() {
+        return Err(anyhow!("no video stream found at {rtsp_url}"));
+    }
+    Ok(infos)
+}
+
+/// A decoded frame in planar I420 (the decoder's native output).
+struct DecodedFrame {
+    width: u32,
+    height: u32,
+    i420: Vec<u8>,
+}
+
+impl DecodedFrame {
+    /// Re-expresses the I420 plane in the publisher's requested format.
+    fn as_format(&self, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Yuv420 => self.i420.clone(),
+            ImageFormat::Rgb888 => {
+                crate::convert::yuv420_to_rgb888(&self.i420, self.width, self.height)
+            }
+            ImageFormat::Nv12 => i420_to_nv12(&self.i420, self.width, self.height),
+            ImageFormat::Gray8 => self.i420[..(self.width * self.height) as usize].to_vec(),
+            // The in-process decoder only emits planar frames; MJPEG is an
+            // ffmpeg-only passthrough rejected before a session starts, so it
+            // can never reach here.
+            ImageFormat::Mjpeg => unreachable!("MJPEG is rejected by the retina backend"),
+        }
+    }
+}
+
+/// Thin wrapper over an in-process H.264 decoder.
+///
+/// Any other codec, H.265 included, is rejected with an error.
+struct Decoder {
+    inner: openh264::decoder::Decoder,
+    /// The `avcC` extradata last fed to the decoder, so SPS/PPS carried
+    /// out-of-band are primed once and re-primed only when they change.
+    params_sent: Option<Vec<u8>>,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self {
+            inner: openh264::decoder::Decoder::new().expect("failed to init H.264 decoder"),
+            params_sent: None,
+        }
+    }
+
+    /// Decodes one access unit, returning a frame once the decoder has enough
+    /// data to emit one. `data` is the AVCC-framed access unit from retina.
+    fn decode(&mut self, params: &VideoParameters, data: &[u8]) -> Result<Option<DecodedFrame>> {
+        if !params.rfc6381_codec().starts_with("avc1") {
+            return Err(anyhow!("unsupported codec {}", params.rfc6381_codec()));
+        }
+
+        // Streams that carry their parameter sets out-of-band never include
+        // SPS/PPS in the per-frame data, so feed them from the stream's
+        // extradata before the first frame (and whenever they change).
+        let extra = params.extra_data();
+        if self.params_sent.as_deref() != Some(extra) {
+            let annex_b = avc_decoder_config_to_annex_b(extra);
+            if !annex_b.is_empty() {
+                self.inner.decode(&annex_b)?;
+            }
+            self.params_sent = Some(extra.to_vec());
+        }
+
+        let annex_b = avcc_to_annex_b(data);
+        let Some(yuv) = self.inner.decode(&annex_b)? else {
+            return Ok(None);
+        };
+
+        let (width, height) = yuv.dimensions();
+        let mut i420 = vec![0u8; width * height * 3 / 2];
+        yuv.write_u8(&mut i420);
+        Ok(Some(DecodedFrame {
+            width: width as u32,
+            height: height as u32,
+            i420,
+        }))
+    }
+}
+
+/// Rewrites length-prefixed (AVCC) NAL units into Annex B start-code framing.
+fn avcc_to_annex_b(data: &[u8]) -> Vec<u8> {
+    const START_CODE: [u8; 4] = [0, 0, 0, 1];
+    let mut out = Vec::with_capacity(data.len() + 16);
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        let len = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        i += 4;
+        if i + len > data.len() {
+            break;
+        }
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(&data[i..i + len]);
+        i += len;
+    }
+    out
+}
+
+/// Converts an AVCDecoderConfigurationRecord (`avcC`), which carries SPS/PPS
+/// out-of-band, into Annex B start-code framing so the decoder can be primed
+/// with the parameter sets. Returns empty on a malformed record.
+fn avc_decoder_config_to_annex_b(extra: &[u8]) -> Vec<u8> {
+    const START_CODE: [u8; 4] = [0, 0, 0, 1];
+    // configurationVersion(1) + profile(3) + lengthSizeMinusOne(1) + numSPS(1)
+    if extra.len() < 6 || extra[0] != 1 {
+        return Vec::new();
+    }
+    let mut out = Vec::new();
+    let mut i = 5;
+    let num_sps = (extra[i] & 0x1f) as usize;
+    i += 1;
+    // SPS set, then the PPS set, each as a 2-byte length followed by the NAL.
+    for _ in 0..num_sps {
+        if i + 2 > extra.len() {
+            return out;
+        }
+        let len = u16::from_be_bytes([extra[i], extra[i + 1]]) as usize;
+        i += 2;
+        if i + len > extra.len() {
+            return out;
+        }
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(&extra[i..i + len]);
+        i += len;
+    }
+    if i >= extra.len() {
+        return out;
+    }
+    let num_pps = extra[i] as usize;
+    i += 1;
+    for _ in 0..num_pps {
+        if i + 2 > extra.len() {
+            return out;
+        }
+        let len = u16::from_be_bytes([extra[i], extra[i + 1]]) as usize;
+        i += 2;
+        if i + len > extra.len() {
+            return out;
+        }
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(&extra[i..i + len]);
+        i += len;
+    }
+    out
+}
+
+/// Repacks a planar I420 buffer into semi-planar NV12 (interleaved UV).
+fn i420_to_nv12(i420: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let chroma = w * h / 4;
+    let y_plane = &i420[..w * h];
+    let u_plane = &i420[w * h..w * h + chroma];
+    let v_plane = &i420[w * h + chroma..];
+
+    let mut nv12 = Vec::with_capacity(w * h * 3 / 2);
+    nv12.extend_from_slice(y_plane);
+    for i in 0..chroma {
+        nv12.push(u_plane[i]);
+        nv12.push(v_plane[i]);
+    }
+    nv12
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avcc_to_annex_b_rewrites_length_prefixes() {
+        // Two NALs: lengths 2 and 3, as 4-byte big-endian AVCC prefixes.
+        let avcc = [0, 0, 0, 2, 0x67, 0x01, 0, 0, 0, 3, 0x68, 0x02, 0x03];
+        let annex_b = avcc_to_annex_b(&avcc);
+        assert_eq!(
+            annex_b,
+            vec![0, 0, 0, 1, 0x67, 0x01, 0, 0, 0, 1, 0x68, 0x02, 0x03]
+        );
+    }
+
+    #[test]
+    fn avcc_to_annex_b_stops_on_truncated_nal() {
+        // Claims a 4-byte NAL but only 1 byte of payload follows.
+        let avcc = [0, 0, 0, 4, 0x67];
+        assert!(avcc_to_annex_b(&avcc).is_empty());
+    }
+
+    #[test]
+    fn avc_decoder_config_extracts_sps_and_pps() {
+        let extra = [
+            1, 0x64, 0x00, 0x1f, 0xff, // version, profile, lengthSizeMinusOne
+            0xe1, // numSPS = 1 (low 5 bits)
+            0, 2, 0x67, 0x64, // SPS: len 2
+            1, // numPPS = 1
+            0, 2, 0x68, 0xce, // PPS: len 2
+        ];
+        let annex_b = avc_decoder_config_to_annex_b(&extra);
+        assert_eq!(
+            annex_b,
+            vec![0, 0, 0, 1, 0x67, 0x64, 0, 0, 0, 1, 0x68, 0xce]
+        );
+    }
+
+    #[test]
+    fn avc_decoder_config_rejects_bad_version() {
+        assert!(avc_decoder_config_to_annex_b(&[0, 1, 2, 3, 4, 5]).is_empty());
+    }
+
+    #[test]
+    fn i420_to_nv12_interleaves_chroma() {
+        // 2x2 frame: 4 Y, 1 U, 1 V.
+        let i420 = [10, 11, 12, 13, /* U */ 200, /* V */ 201];
+        let nv12 = i420_to_nv12(&i420, 2, 2);
+        assert_eq!(nv12, vec![10, 11, 12, 13, 200, 201]);
+    }
+}
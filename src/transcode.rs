@@ -0,0 +1,375 @@
+//! Encoder stage for `OUTPUT_MODE=encoded`.
+//!
+//! Instead of decoding to raw RGB/YUV, this path asks ffmpeg to re-mux (`-c:v
+//! copy`) or re-encode the stream to H.264/VP9 at a target bitrate/resolution
+//! and forwards the resulting compressed packets, flagged as keyframe or delta,
+//! for publishing on the `IMAGE_COMPRESSED` topic. This is dramatically cheaper
+//! on the wire than raw frames when many cameras are streamed at once.
+
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::FfmpegEvent;
+use log::{error, info, warn};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::probe::StreamInfo;
+use crate::{format_entity_path, CameraLiveness, RtspTransport, TranscodeCodec, TranscodeConfig};
+
+/// One compressed packet ready to publish.
+pub struct CompressedFrame {
+    pub entity_path: String,
+    pub codec: TranscodeCodec,
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Runs a single encoding session to completion, forwarding compressed packets
+/// over `sink`. Returns when ffmpeg exits; the supervisor restarts it.
+pub fn run_ffmpeg_encoded_session(
+    rtsp_url: &str,
+    camera_idx: usize,
+    transport: RtspTransport,
+    transcode: &TranscodeConfig,
+    stream_info: &StreamInfo,
+    liveness: &CameraLiveness,
+    sink: &UnboundedSender<CompressedFrame>,
+) {
+    let entity_path = format_entity_path(rtsp_url);
+
+    let mut command = FfmpegCommand::new();
+    command.args([
+        "-rtsp_transport", transport.ffmpeg_flag(),
+        "-timeout", "5000000",
+        "-allowed_media_types", "video",
+    ]);
+    command.input(rtsp_url);
+    apply_codec_args(&mut command, transcode);
+
+    let mut child = command
+        .pipe_stdout()
+        .spawn()
+        .expect(&format!("Failed to spawn ffmpeg encoder (camera {camera_idx})"));
+
+    let codec = transcode.codec;
+    let (width, height) = (stream_info.width, stream_info.height);
+    // The raw `-f h264`/`-f ivf` byte stream arrives in arbitrary pipe-sized
+    // chunks that don't align to coded pictures, so reassemble whole packets
+    // before publishing — one `CompressedFrame` is then exactly one packet.
+    let mut splitter = PacketSplitter::for_codec(codec);
+    let mut packets = Vec::new();
+
+    if let Ok(iter) = child.iter() {
+        for event in iter {
+            match event {
+                FfmpegEvent::OutputChunk(chunk) => {
+                    splitter.push(&chunk, &mut packets);
+                    if !drain_packets(
+                        &mut packets, sink, &entity_path, codec, width, height, liveness,
+                    ) {
+                        error!("[camera {camera_idx}] Compressed channel closed, stopping encoder");
+                        return;
+                    }
+                }
+                FfmpegEvent::Log(ffmpeg_sidecar::event::LogLevel::Error, msg) => {
+                    error!("[camera {camera_idx}] ffmpeg encoder: {msg}");
+                }
+                FfmpegEvent::Done => {
+                    info!("[camera {camera_idx}] ffmpeg encoder done");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Publish any packet still buffered when the stream ended.
+    splitter.flush(&mut packets);
+    drain_packets(&mut packets, sink, &entity_path, codec, width, height, liveness);
+}
+
+/// Sends each reassembled packet as one `CompressedFrame`. Returns `false` once
+/// the sink is closed so the caller can stop the encoder.
+#[allow(clippy::too_many_arguments)]
+fn drain_packets(
+    packets: &mut Vec<(Vec<u8>, bool)>,
+    sink: &UnboundedSender<CompressedFrame>,
+    entity_path: &str,
+    codec: TranscodeCodec,
+    width: u32,
+    height: u32,
+    liveness: &CameraLiveness,
+) -> bool {
+    for (data, keyframe) in packets.drain(..) {
+        liveness.mark_frame();
+        let frame = CompressedFrame {
+            entity_path: entity_path.to_string(),
+            codec,
+            data,
+            keyframe,
+            width,
+            height,
+        };
+        if sink.send(frame).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Reassembles the raw ffmpeg byte stream into whole coded pictures, since pipe
+/// chunks don't align to picture boundaries. Each emitted packet carries its
+/// own keyframe flag.
+enum PacketSplitter {
+    /// Annex-B H.264: split into access units at VCL-NAL boundaries.
+    AnnexB {
+        buf: Vec<u8>,
+        au: Vec<u8>,
+        au_has_vcl: bool,
+        au_keyframe: bool,
+    },
+    /// IVF (VP9): a 32-byte file header then 12-byte-prefixed frames.
+    Ivf { buf: Vec<u8>, header_parsed: bool },
+}
+
+impl PacketSplitter {
+    fn for_codec(codec: TranscodeCodec) -> Self {
+        match codec {
+            TranscodeCodec::Vp9 => PacketSplitter::Ivf {
+                buf: Vec::new(),
+                header_parsed: false,
+            },
+            TranscodeCodec::H264 | TranscodeCodec::Copy => PacketSplitter::AnnexB {
+                buf: Vec::new(),
+                au: Vec::new(),
+                au_has_vcl: false,
+                au_keyframe: false,
+            },
+        }
+    }
+
+    /// Feeds a chunk and appends any now-complete packets (with keyframe flag).
+    fn push(&mut self, chunk: &[u8], out: &mut Vec<(Vec<u8>, bool)>) {
+        match self {
+            PacketSplitter::AnnexB {
+                buf,
+                au,
+                au_has_vcl,
+                au_keyframe,
+            } => {
+                buf.extend_from_slice(chunk);
+                // Extract NALs bounded by consecutive start codes; the final one
+                // may be incomplete, so retain from the last start code onward.
+                let starts = start_code_offsets(buf);
+                if starts.len() < 2 {
+                    return;
+                }
+                let last = *starts.last().unwrap();
+                let nals: Vec<Vec<u8>> = starts
+                    .windows(2)
+                    .map(|w| buf[w[0]..w[1]].to_vec())
+                    .collect();
+                buf.drain(..last);
+                // Group NALs into access units. A new picture starts at an
+                // access-unit delimiter, or at the first slice of a picture
+                // (`first_mb_in_slice == 0`). Keying on "VCL after VCL" would
+                // split multi-slice pictures into several packets, so we don't.
+                for nal in &nals {
+                    let kind = nal.get(3).map(|b| b & 0x1f);
+                    let is_vcl = matches!(kind, Some(1..=5));
+                    let is_aud = kind == Some(9);
+                    // `first_mb_in_slice` is the leading `ue(v)` of the slice
+                    // header; the value 0 is the single bit `1`, so the high bit
+                    // of the first RBSP byte is set exactly for a picture's first
+                    // slice.
+                    let first_slice =
+                        is_vcl && nal.get(4).map(|b| b & 0x80 != 0).unwrap_or(false);
+                    let starts_new_au =
+                        (is_aud && !au.is_empty()) || (first_slice && *au_has_vcl);
+                    if starts_new_au {
+                        out.push((std::mem::take(au), *au_keyframe));
+                        *au_has_vcl = false;
+                        *au_keyframe = false;
+                    }
+                    au.extend_from_slice(nal);
+                    if is_vcl {
+                        *au_has_vcl = true;
+                        if kind == Some(5) {
+                            *au_keyframe = true;
+                        }
+                    }
+                }
+            }
+            PacketSplitter::Ivf { buf, header_parsed } => {
+                buf.extend_from_slice(chunk);
+                if !*header_parsed {
+                    if buf.len() < 32 {
+                        return;
+                    }
+                    buf.drain(..32);
+                    *header_parsed = true;
+                }
+                loop {
+                    if buf.len() < 12 {
+                        break;
+                    }
+                    let size = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+                    if buf.len() < 12 + size {
+                        break;
+                    }
+                    let payload = buf[12..12 + size].to_vec();
+                    buf.drain(..12 + size);
+                    // VP9 uncompressed header, MSB first: frame_marker (2 bits),
+                    // profile_low_bit, profile_high_bit, a reserved bit for
+                    // profile 3 only, show_existing_frame, then frame_type
+                    // (0 = key frame).
+                    let keyframe = payload.first().map_or(false, |&b| {
+                        let profile = ((b >> 5) & 1) | (((b >> 4) & 1) << 1);
+                        let shift: u32 = if profile == 3 { 2 } else { 3 };
+                        b >> 6 == 2 && (b >> shift) & 1 == 0 && (b >> (shift - 1)) & 1 == 0
+                    });
+                    out.push((payload, keyframe));
+                }
+            }
+        }
+    }
+
+    /// Emits any packet still buffered when the stream ends.
+    fn flush(&mut self, out: &mut Vec<(Vec<u8>, bool)>) {
+        if let PacketSplitter::AnnexB {
+            au,
+            au_has_vcl,
+            au_keyframe,
+            ..
+        } = self
+        {
+            if *au_has_vcl && !au.is_empty() {
+                out.push((std::mem::take(au), *au_keyframe));
+                *au_has_vcl = false;
+                *au_keyframe = false;
+            }
+        }
+    }
+}
+
+/// Offsets of every 3-byte (`00 00 01`) Annex-B start code in `data`.
+fn start_code_offsets(data: &[u8]) -> Vec<usize> {
+    let mut offs = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            offs.push(i);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    offs
+}
+
+/// Appends the output codec/bitrate/scale/GOP flags for this pipeline.
+fn apply_codec_args(command: &mut FfmpegCommand, transcode: &TranscodeConfig) {
+    match transcode.codec {
+        TranscodeCodec::Copy => {
+            command.args(["-c:v", "copy", "-f", "h264"]);
+        }
+        TranscodeCodec::H264 => {
+            command.args(["-c:v", "libx264", "-preset", "veryfast", "-f", "h264"]);
+        }
+        TranscodeCodec::Vp9 => {
+            command.args(["-c:v", "libvpx-vp9", "-f", "ivf"]);
+        }
+    }
+
+    if !matches!(transcode.codec, TranscodeCodec::Copy) {
+        if let Some(bitrate) = transcode.bitrate_kbps {
+            command.args(["-b:v", &format!("{bitrate}k")]);
+        }
+        if let Some(max_width) = transcode.max_width {
+            // Preserve aspect ratio; `-2` keeps the height divisible by 2.
+            command.args(["-vf", &format!("scale='min({max_width},iw)':-2")]);
+        }
+        if let Some(gop) = transcode.gop {
+            command.args(["-g", &gop.to_string()]);
+        }
+    } else if transcode.bitrate_kbps.is_some() || transcode.max_width.is_some() {
+        warn!("bitrate/max-width ignored for copy passthrough");
+    }
+
+    command.args(["-an"]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an Annex-B NAL with a 3-byte start code: `nal_type`, then `rbsp`.
+    fn nal(nal_type: u8, rbsp: &[u8]) -> Vec<u8> {
+        let mut v = vec![0, 0, 1, nal_type & 0x1f];
+        v.extend_from_slice(rbsp);
+        v
+    }
+
+    #[test]
+    fn start_code_offsets_finds_every_start() {
+        let data = [0, 0, 1, 0x67, 9, 0, 0, 1, 0x65];
+        assert_eq!(start_code_offsets(&data), vec![0, 5]);
+    }
+
+    #[test]
+    fn annexb_keeps_multi_slice_picture_in_one_packet() {
+        // SPS, PPS, then an IDR picture split into two slices (first_mb_in_slice
+        // 0 then non-zero), followed by the first slice of the next picture.
+        let mut splitter = PacketSplitter::for_codec(TranscodeCodec::H264);
+        let mut stream = Vec::new();
+        stream.extend(nal(7, &[0x42])); // SPS
+        stream.extend(nal(8, &[0xce])); // PPS
+        stream.extend(nal(5, &[0x80])); // IDR slice 0 (first_mb_in_slice == 0)
+        stream.extend(nal(5, &[0x20])); // IDR slice 1 (first_mb_in_slice != 0)
+        stream.extend(nal(1, &[0x80])); // next picture, first slice
+        stream.extend(nal(1, &[0x20])); // trailing incomplete NAL, retained
+
+        let mut out = Vec::new();
+        splitter.push(&stream, &mut out);
+
+        // Only the first (IDR) access unit is complete; it holds all its slices
+        // and carries the keyframe flag.
+        assert_eq!(out.len(), 1);
+        let (data, keyframe) = &out[0];
+        assert!(keyframe);
+        assert_eq!(start_code_offsets(data).len(), 4); // SPS + PPS + 2 slices
+    }
+
+    #[test]
+    fn annexb_flush_emits_trailing_picture() {
+        let mut splitter = PacketSplitter::for_codec(TranscodeCodec::Copy);
+        let mut stream = Vec::new();
+        stream.extend(nal(5, &[0x80])); // IDR first slice
+        stream.extend(nal(1, &[0x80])); // picture 2 first slice -> closes IDR
+        stream.extend(nal(1, &[0x80])); // picture 3 first slice -> closes pic 2
+
+        let mut out = Vec::new();
+        splitter.push(&stream, &mut out);
+        // push processes all but the retained last NAL (picture 3), so only the
+        // IDR's boundary (at picture 2) has been seen.
+        assert_eq!(out.len(), 1);
+        splitter.flush(&mut out);
+        assert_eq!(out.len(), 2); // buffered picture 2 flushed
+        assert!(out[0].1 && !out[1].1);
+    }
+
+    #[test]
+    fn ivf_splits_on_frame_headers() {
+        let mut splitter = PacketSplitter::for_codec(TranscodeCodec::Vp9);
+        let mut stream = vec![0u8; 32]; // IVF file header (contents unused)
+        for payload in [[0xaa, 0xbb], [0xcc, 0xdd]] {
+            stream.extend((payload.len() as u32).to_le_bytes());
+            stream.extend([0u8; 8]); // 8-byte timestamp
+            stream.extend(payload);
+        }
+        let mut out = Vec::new();
+        splitter.push(&stream, &mut out);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].0, vec![0xaa, 0xbb]);
+        assert_eq!(out[1].0, vec![0xcc, 0xdd]);
+    }
+}